@@ -1,17 +1,19 @@
-use factorial::Factorial;
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
 use ndarray::{prelude::*, ViewRepr};
-use ndarray_linalg::{error::LinalgError, solve::Determinant};
+use num_bigint::BigInt;
+use num_rational::Ratio;
+use num_traits::{ToPrimitive, Zero};
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 #[derive(Debug, PartialEq)]
 pub enum DMIError {
-    Arithmetic,
     /// The values of answers must be integers in [0, C)
     AnswerValsOutOfScope,
-    // More specific error for differentiating different errors internally
-    FactorialCalc,
-    FactorialMulCalc,
-    Exponentiate,
-    LinalgError,
     NLessThanM,
     PaymentNLessThanM,
     // At least one agent should have been engaged
@@ -20,34 +22,250 @@ pub enum DMIError {
     TooFewTasks,
     /// Only one agent or fewer given when calculating payments
     TooFewAgentsForPaymentCalc,
-    PaymentFactorialCalc,
+    /// `do_dmi_rounds` needs at least one round to average over
+    TooFewRounds,
 }
 
-impl From<LinalgError> for DMIError {
-    fn from(_: LinalgError) -> DMIError {
-        DMIError::LinalgError
+// Bareiss fraction-free elimination: stays exact, no float precision loss.
+fn bareiss_determinant(matrix: &Array2<BigInt>) -> BigInt {
+    let n = matrix.nrows();
+    if n == 0 {
+        return BigInt::from(1);
     }
+
+    let mut m = matrix.clone();
+    let mut sign = BigInt::from(1);
+    let mut prev = BigInt::from(1);
+
+    for k in 0..n - 1 {
+        if m[[k, k]].is_zero() {
+            match ((k + 1)..n).find(|&i| !m[[i, k]].is_zero()) {
+                Some(swap_row) => {
+                    for col in 0..n {
+                        m.swap((k, col), (swap_row, col));
+                    }
+                    sign = -sign;
+                }
+                None => return BigInt::zero(),
+            }
+        }
+
+        for i in (k + 1)..n {
+            for j in (k + 1)..n {
+                m[[i, j]] = (&m[[i, j]] * &m[[k, k]] - &m[[i, k]] * &m[[k, j]]) / &prev;
+            }
+        }
+        prev = m[[k, k]].clone();
+    }
+
+    sign * m[[n - 1, n - 1]].clone()
+}
+
+fn mulmod(a: u64, b: u64, p: u64) -> u64 {
+    ((a as u128 * b as u128) % p as u128) as u64
 }
 
+fn powmod(mut base: u64, mut exp: u64, p: u64) -> u64 {
+    let mut acc = 1 % p;
+    base %= p;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = mulmod(acc, base, p);
+        }
+        base = mulmod(base, base, p);
+        exp >>= 1;
+    }
+    acc
+}
+
+// Fermat-based modular inverse: only valid when `p` is prime.
+fn invmod(a: u64, p: u64) -> u64 {
+    powmod(a, p - 2, p)
+}
+
+// Deterministic Miller-Rabin: this witness set is proven correct for all u64.
+fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    for &w in &WITNESSES {
+        if n == w {
+            return true;
+        }
+        if n.is_multiple_of(w) {
+            return false;
+        }
+    }
+
+    let mut d = n - 1;
+    let mut r = 0;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        r += 1;
+    }
+
+    'witness: for &a in &WITNESSES {
+        let mut x = powmod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 0..r - 1 {
+            x = mulmod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Largest prime <= `below | 1` (for an even `below` that is `below + 1`),
+// searching downward.
+fn prev_prime(below: u64) -> u64 {
+    let mut candidate = below | 1;
+    while !is_prime(candidate) {
+        candidate -= 2;
+    }
+    candidate
+}
+
+// Gaussian elimination over the field Z/pZ.
+fn det_mod_p(matrix: &Array2<BigInt>, p: u64) -> u64 {
+    let n = matrix.nrows();
+    if n == 0 {
+        return 1 % p;
+    }
+
+    let bp = BigInt::from(p);
+    let mut m: Vec<Vec<u64>> = matrix
+        .outer_iter()
+        .map(|row| {
+            row.iter()
+                .map(|v| (((v % &bp) + &bp) % &bp).to_u64().unwrap())
+                .collect()
+        })
+        .collect();
+
+    let mut det = 1 % p;
+    for k in 0..n {
+        if m[k][k] == 0 {
+            match ((k + 1)..n).find(|&i| m[i][k] != 0) {
+                Some(swap_row) => {
+                    m.swap(k, swap_row);
+                    det = (p - det) % p;
+                }
+                None => return 0,
+            }
+        }
+
+        let pivot = m[k][k];
+        det = mulmod(det, pivot, p);
+        let inv = invmod(pivot, p);
+
+        let pivot_row: Vec<u64> = m[k][k..].to_vec();
+        for row in m[(k + 1)..].iter_mut() {
+            let factor = mulmod(row[k], inv, p);
+            if factor == 0 {
+                continue;
+            }
+            for (dest, &pv) in row[k..].iter_mut().zip(pivot_row.iter()) {
+                let sub = mulmod(factor, pv, p);
+                *dest = (*dest + p - sub) % p;
+            }
+        }
+    }
+
+    det
+}
+
+// Hadamard bound squared: H^2 = prod_i (sum_j M[i][j]^2).
+fn hadamard_bound_sq(matrix: &Array2<BigInt>) -> BigInt {
+    matrix
+        .outer_iter()
+        .map(|row| row.iter().map(|v| v * v).fold(BigInt::zero(), |a, b| a + b))
+        .fold(BigInt::from(1), |a, b| a * b)
+}
+
+// det(M) mod several large primes via Gaussian elimination, reconstructed by CRT.
+fn modular_crt_determinant(matrix: &Array2<BigInt>) -> BigInt {
+    let target = hadamard_bound_sq(matrix) * 4; // compare against (2H)^2
+
+    let mut chosen: Vec<u64> = vec![];
+    let mut product = BigInt::from(1);
+    let mut candidate = u32::MAX as u64; // ~32-bit primes: plenty of headroom under u128 widening in mulmod
+    while &product * &product <= target {
+        let p = prev_prime(candidate);
+        chosen.push(p);
+        product *= p;
+        candidate = p - 2; // `below | 1` would just re-find `p` itself, since p is odd
+    }
+
+    let mut x = BigInt::zero();
+    for &p in &chosen {
+        let residue = det_mod_p(matrix, p);
+        let partial_modulus = &product / p;
+        let partial_mod_p = (&partial_modulus % p).to_u64().unwrap();
+        let inv = invmod(partial_mod_p, p);
+        x += BigInt::from(mulmod(residue, inv, p)) * &partial_modulus;
+    }
+    x %= &product;
+
+    if &x * 2 > product {
+        x - product
+    } else {
+        x
+    }
+}
+
+/// Which exact-determinant algorithm `get_mutual_information` should use.
+pub enum DetStrategy {
+    Bareiss,
+    ModularCrt,
+}
+
+// factorial(n) as an exact BigInt, since `calculate_factorials` needs
+// binomial coefficients well beyond what any machine integer can hold once
+// T >= 2C starts to bite.
+fn big_factorial(n: &usize) -> BigInt {
+    (1..=*n).fold(BigInt::from(1), |acc, i| acc * i)
+}
+
+// Payments as an approximate f64, for display only; not available under no_std.
+#[cfg(feature = "std")]
+pub fn payments_as_f64(payments: &[Ratio<BigInt>]) -> Vec<f64> {
+    payments
+        .iter()
+        .map(|p| p.to_f64().unwrap_or(f64::NAN))
+        .collect()
+}
+
+// Locked to BigInt/Ratio<BigInt> rather than generic over a numeric type:
+// exactness (no float drift) is the point, and BigInt is the only type on
+// hand that stays exact at the sizes DMI's factorials/determinants reach.
 pub trait DMI {
-    // factorial(n) / (factorial(m) * factorial(n - m))
-    fn calculate_factorials(n: &usize, m: &usize) -> Result<f32, DMIError> {
-        let factorial_n = Factorial::checked_factorial(n).ok_or(DMIError::FactorialCalc)?;
-        let factorial_mul_result = {
-            let factorial_m = Factorial::checked_factorial(m).ok_or(DMIError::FactorialCalc)?;
+    /// Which exact determinant algorithm to use. Defaults to Bareiss.
+    fn det_strategy() -> DetStrategy {
+        DetStrategy::Bareiss
+    }
+
+    fn determinant(matrix: &Array2<BigInt>) -> BigInt {
+        match Self::det_strategy() {
+            DetStrategy::Bareiss => bareiss_determinant(matrix),
+            DetStrategy::ModularCrt => modular_crt_determinant(matrix),
+        }
+    }
 
-            let factorial_n_minus_m =
-                Factorial::checked_factorial(&(n.checked_sub(*m).ok_or(DMIError::NLessThanM)?))
-                    .ok_or(DMIError::FactorialCalc)?;
+    // factorial(n) / (factorial(m) * factorial(n - m))
+    fn calculate_factorials(n: &usize, m: &usize) -> Result<BigInt, DMIError> {
+        let n_minus_m = n.checked_sub(*m).ok_or(DMIError::NLessThanM)?;
 
-            factorial_m
-                .checked_mul(factorial_n_minus_m)
-                .ok_or(DMIError::FactorialMulCalc)?
-        };
+        let factorial_n = big_factorial(n);
+        let factorial_m = big_factorial(m);
+        let factorial_n_minus_m = big_factorial(&n_minus_m);
 
-        Ok(factorial_n
-            .checked_div(factorial_mul_result)
-            .ok_or(DMIError::Arithmetic)? as f32)
+        Ok(factorial_n / (factorial_m * factorial_n_minus_m))
     }
 
     fn check_answers(x: &usize, c: &usize) -> bool {
@@ -56,16 +274,16 @@ pub trait DMI {
 
     // get M mechanism
     // a and b are equal length
-    fn get_mechanism<'a>(
+    fn get_mechanism(
         a: ArrayView1<usize>,
         b: ArrayView1<usize>,
         c: &usize,
-    ) -> Result<Array2<f32>, DMIError> {
-        let mut mechanism = Array2::<f32>::zeros((*c, *c));
-        for (x, y) in a.into_iter().zip(b.into_iter()) {
-            if Self::check_answers(&x, &c) && Self::check_answers(&y, &c) {
+    ) -> Result<Array2<BigInt>, DMIError> {
+        let mut mechanism = Array2::<BigInt>::zeros((*c, *c));
+        for (x, y) in a.into_iter().zip(b) {
+            if Self::check_answers(x, c) && Self::check_answers(y, c) {
                 if let Some(v) = mechanism.get_mut((*x, *y)) {
-                    *v += 1.;
+                    *v += 1;
                 }
             } else {
                 return Err(DMIError::AnswerValsOutOfScope);
@@ -82,15 +300,15 @@ pub trait DMI {
         a2: ArrayView1<usize>,
         b2: ArrayView1<usize>,
         c: &usize,
-    ) -> Result<f32, DMIError> {
+    ) -> Result<BigInt, DMIError> {
         let m1 = Self::get_mechanism(a1, b1, c)?;
         let m2 = Self::get_mechanism(a2, b2, c)?;
-        Ok(m1.det()? * m2.det()?)
+        Ok(Self::determinant(&m1) * Self::determinant(&m2))
     }
 
     // Do the actual DMI calculation
     // Note: the size of the returned vector is predictably larger than the same calculation done in the python version
-    fn do_dmi(answers: Array2<usize>, choice_n: usize) -> Result<Vec<f32>, DMIError> {
+    fn do_dmi(answers: Array2<usize>, choice_n: usize) -> Result<Vec<Ratio<BigInt>>, DMIError> {
         let answers_shape = answers.shape();
         let agent_n = answers_shape[0];
         let task_n = answers_shape[1];
@@ -103,10 +321,9 @@ pub trait DMI {
             return Err(DMIError::TooFewAgents);
         }
 
-        // Arbitrarily split answers
+        // Split answers at the midpoint
         let transposed = answers.t();
         let view = ArrayView2::from(transposed);
-        // TODO: shuffle all answers here
         // get half and split array at it
         let half = task_n / 2;
         // t1, and t2
@@ -116,10 +333,38 @@ pub trait DMI {
         let first_half_answers = first_half_answers.t();
         let second_half_answers = second_half_answers.t();
 
-        let payments =
-            Self::calculate_payments(&agent_n, &choice_n, first_half_answers, second_half_answers);
+        Self::calculate_payments(&agent_n, &choice_n, first_half_answers, second_half_answers)
+    }
+
+    // Average `do_dmi` over `rounds` seeded random task-column permutations.
+    fn do_dmi_rounds(
+        answers: Array2<usize>,
+        choice_n: usize,
+        rounds: usize,
+        seed: u64,
+    ) -> Result<Vec<Ratio<BigInt>>, DMIError> {
+        if rounds == 0 {
+            return Err(DMIError::TooFewRounds);
+        }
+
+        let task_n = answers.shape()[1];
+        let mut task_order: Vec<usize> = (0..task_n).collect();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        // First round keeps the original column order, so `rounds == 1` is
+        // exactly the same split `do_dmi` would have produced on its own.
+        let mut totals = Self::do_dmi(answers.select(Axis(1), &task_order), choice_n)?;
+        for _ in 1..rounds {
+            task_order.shuffle(&mut rng);
+            let shuffled = answers.select(Axis(1), &task_order);
+            let round_payments = Self::do_dmi(shuffled, choice_n)?;
+            for (total, payment) in totals.iter_mut().zip(round_payments) {
+                *total += payment;
+            }
+        }
 
-        payments
+        let rounds = Ratio::from_integer(BigInt::from(rounds));
+        Ok(totals.into_iter().map(|p| p / &rounds).collect())
     }
 
     fn calculate_payments(
@@ -127,40 +372,100 @@ pub trait DMI {
         choice_n: &usize,
         t1: ArrayBase<ViewRepr<&usize>, Dim<[usize; 2]>>,
         t2: ArrayBase<ViewRepr<&usize>, Dim<[usize; 2]>>,
-    ) -> Result<Vec<f32>, DMIError> {
+    ) -> Result<Vec<Ratio<BigInt>>, DMIError> {
         let prelim_agents = (agent_n.checked_sub(1)).ok_or(DMIError::TooFewAgentsForPaymentCalc)?;
-        let fact = Factorial::checked_factorial(choice_n).ok_or(DMIError::PaymentFactorialCalc)?;
-        let raised = fact.checked_pow(2).ok_or(DMIError::Exponentiate)?;
+        let fact = big_factorial(choice_n);
+        let raised = &fact * &fact;
 
-        let mut norm_factor = prelim_agents
-            .checked_mul(raised)
-            .ok_or(DMIError::Arithmetic)? as f32;
-
-        norm_factor *= Self::calculate_factorials(&t1.shape()[0], choice_n)?
+        let norm_factor = BigInt::from(prelim_agents)
+            * raised
+            * Self::calculate_factorials(&t1.shape()[0], choice_n)?
             * Self::calculate_factorials(&t2.shape()[0], choice_n)?;
 
+        let norm_factor = Ratio::from_integer(norm_factor);
+
         let mut payments = vec![];
         for i in 0..*agent_n {
-            let mut p = 0_f32;
+            let mut p = Ratio::<BigInt>::zero();
             for j in 0..*agent_n {
                 if i == j {
                     continue;
                 }
 
-                p += Self::get_mutual_information(
+                let dmi = Self::get_mutual_information(
                     t1.slice(s![i, ..,]),
                     t1.slice(s![j, ..,]),
                     t2.slice(s![i, ..,]),
                     t2.slice(s![j, ..,]),
                     choice_n,
-                )
-                .unwrap();
+                )?;
 
-                p /= norm_factor;
-                payments.push(p);
+                p += Ratio::from_integer(dmi) / &norm_factor;
             }
+            payments.push(p);
         }
 
         Ok(payments)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    struct WithBareiss;
+    impl DMI for WithBareiss {}
+
+    struct WithModularCrt;
+    impl DMI for WithModularCrt {
+        fn det_strategy() -> DetStrategy {
+            DetStrategy::ModularCrt
+        }
+    }
+
+    #[test]
+    fn modular_crt_matches_bareiss() {
+        let small = array![[1, 2], [3, 4]].mapv(BigInt::from);
+        let mixed_signs = array![[5, 0, -2], [1, -3, 4], [2, 2, 1]].mapv(BigInt::from);
+        // Large entries to exercise CRT's prime-growth loop, not just its first prime.
+        let large_entries = array![
+            [5000, 0, 0, 0],
+            [0, 5000, 0, 0],
+            [0, 0, 5000, 0],
+            [0, 0, 0, 5000]
+        ]
+        .mapv(BigInt::from);
+
+        for matrix in [small, mixed_signs, large_entries] {
+            assert_eq!(
+                WithBareiss::determinant(&matrix),
+                WithModularCrt::determinant(&matrix)
+            );
+        }
+    }
+
+    #[test]
+    fn determinant_forces_a_pivot_swap() {
+        let needs_swap = array![[0, 1], [1, 0]].mapv(BigInt::from);
+        let expected = BigInt::from(-1);
+        assert_eq!(WithBareiss::determinant(&needs_swap), expected);
+        assert_eq!(WithModularCrt::determinant(&needs_swap), expected);
+    }
+
+    #[test]
+    fn do_dmi_rounds_with_one_round_matches_do_dmi() {
+        let answers = array![[0, 1, 0, 1, 0, 1], [1, 0, 1, 0, 1, 0], [0, 0, 1, 1, 0, 1]];
+        assert_eq!(
+            WithBareiss::do_dmi(answers.clone(), 2).unwrap(),
+            WithBareiss::do_dmi_rounds(answers, 2, 1, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn calculate_payments_has_one_entry_per_agent() {
+        let answers = array![[0, 1, 0, 1, 0, 1], [1, 0, 1, 0, 1, 0], [0, 0, 1, 1, 0, 1]];
+        let payments = WithBareiss::do_dmi(answers, 2).unwrap();
+        assert_eq!(payments.len(), 3);
+    }
+}
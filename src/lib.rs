@@ -0,0 +1,8 @@
+// Pairs with a default-enabled `std` feature in Cargo.toml (also gating
+// default-features for ndarray/num-bigint/num-rational).
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+pub mod dmi;